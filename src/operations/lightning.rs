@@ -1,12 +1,15 @@
 use crate::{
     data::constants::LNDHUB_ENDPOINT,
+    operations::price::{fetch_rate, RateCache},
     util::{get, post_json_auth},
 };
-use anyhow::{Ok, Result};
-use lightning_invoice::Invoice;
+use anyhow::{bail, Ok, Result};
+use lightning_invoice::{Currency, Invoice, InvoiceDescription};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::future::Future;
 use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Lightning wallet credentials
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -96,6 +99,8 @@ pub struct Transaction {
     pub tx_type: String,
     pub fees: String,
     pub reference: Option<String>,
+    /// Set when this transaction is the Lightning-side credit of a submarine swap-in
+    pub swap_id: Option<String>,
 }
 
 /// Pay invoice response
@@ -143,18 +148,128 @@ pub async fn auth(username: &str, password: &str) -> Result<Tokens> {
     Ok(tokens)
 }
 
+/// Refresh token request
+#[derive(Debug, Serialize, Deserialize)]
+struct RefreshTokenRequest {
+    refresh_token: String,
+}
+
+/// Exchanges a refresh token for a new token pair, avoiding a full re-`auth()`
+/// with the user's username and password
+pub async fn refresh_token(refresh: &str) -> Result<Tokens> {
+    let req = RefreshTokenRequest {
+        refresh_token: refresh.to_string(),
+    };
+    let endpoint = LNDHUB_ENDPOINT.to_string();
+    let auth_url = format!("{endpoint}/auth?type=refresh_token");
+    let response = post_json_auth(&auth_url, &Some(req), None).await?;
+    let tokens: Tokens = serde_json::from_str(&response)?;
+
+    Ok(tokens)
+}
+
+/// LNDHub's in-band error shape: requests that fail return HTTP 200 with a body
+/// like `{"error":true,"code":1,"message":"bad auth"}` instead of the struct the
+/// endpoint normally returns, which just fails `serde_json::from_str` with an
+/// unrelated "missing field" error
+#[derive(Debug, Deserialize)]
+struct LndhubErrorResponse {
+    error: bool,
+    code: i32,
+    message: String,
+}
+
+/// LNDHub's error code for an expired or otherwise invalid access token
+const LNDHUB_BAD_AUTH_CODE: i32 = 1;
+
+/// Marker error so `is_auth_error` can recognize a bad-auth response without
+/// string-matching the error message
+#[derive(Debug)]
+struct LndhubAuthError(String);
+
+impl std::fmt::Display for LndhubAuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "lndhub auth error: {}", self.0)
+    }
+}
+
+impl std::error::Error for LndhubAuthError {}
+
+/// Parses an LNDHub response body into `T`, first checking for the API's
+/// in-band `{"error":true,...}` shape so auth failures are recognized as such
+/// rather than as a generic deserialization error
+pub(crate) fn parse_lndhub_response<T: serde::de::DeserializeOwned>(response: &str) -> Result<T> {
+    if let std::result::Result::Ok(err) = serde_json::from_str::<LndhubErrorResponse>(response) {
+        if err.error {
+            if err.code == LNDHUB_BAD_AUTH_CODE {
+                return Err(anyhow::Error::new(LndhubAuthError(err.message)));
+            }
+            anyhow::bail!("lndhub error {}: {}", err.code, err.message);
+        }
+    }
+
+    let value: T = serde_json::from_str(response)?;
+    Ok(value)
+}
+
+/// True if an LNDHub error response indicates the access token expired or was rejected
+fn is_auth_error(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<LndhubAuthError>().is_some()
+}
+
+/// Runs `call` with `token`, transparently refreshing the session via `refresh`
+/// and retrying once if the call fails because the token expired
+pub(crate) async fn with_refresh<T, F, Fut>(token: &str, refresh: &str, call: F) -> Result<T>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    match call(token.to_string()).await {
+        std::result::Result::Ok(value) => Ok(value),
+        Err(err) if is_auth_error(&err) => {
+            let tokens = refresh_token(refresh).await?;
+            call(tokens.token).await
+        }
+        Err(err) => Err(err),
+    }
+}
+
 /// Creates a lightning invoice
 pub async fn create_invoice(
     description: &str,
     amount: u32,
     token: &str,
+    refresh: &str,
 ) -> Result<AddInvoiceResponse> {
     let endpoint = LNDHUB_ENDPOINT.to_string();
     let amount = amount as f32 / 100_000_000.0;
     let amt_str = amount.to_string();
-    let url = format!("{endpoint}/addinvoice?amount={amt_str}&meta={description}");
-    let response = get(&url, Some(token)).await?;
-    let invoice: AddInvoiceResponse = serde_json::from_str(&response)?;
+    let description = description.to_string();
+    with_refresh(token, refresh, move |token| {
+        let url = format!("{endpoint}/addinvoice?amount={amt_str}&meta={description}");
+        async move {
+            let response = get(&url, Some(&token)).await?;
+            let invoice: AddInvoiceResponse = parse_lndhub_response(&response)?;
+            Ok(invoice)
+        }
+    })
+    .await
+}
+
+/// Creates a lightning invoice for a fiat amount, converting it to sats at the
+/// current `currency` exchange rate and recording the rate that was applied
+pub async fn create_invoice_fiat(
+    description: &str,
+    fiat_amount: f64,
+    currency: &str,
+    token: &str,
+    refresh: &str,
+) -> Result<AddInvoiceResponse> {
+    let rate = fetch_rate(currency, None).await?;
+    let sats = (fiat_amount / rate * 100_000_000.0).round() as u32;
+
+    let mut invoice = create_invoice(description, sats, token, refresh).await?;
+    invoice.rate = Some(rate.to_string());
 
     Ok(invoice)
 }
@@ -166,39 +281,314 @@ pub fn decode_invoice(payment_request: &str) -> Result<Invoice> {
     Ok(invoice)
 }
 
+/// Network an invoice was generated for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InvoiceNetwork {
+    Bitcoin,
+    Testnet,
+    Signet,
+    Regtest,
+}
+
+impl From<Currency> for InvoiceNetwork {
+    fn from(currency: Currency) -> Self {
+        match currency {
+            Currency::Bitcoin => InvoiceNetwork::Bitcoin,
+            Currency::BitcoinTestnet => InvoiceNetwork::Testnet,
+            Currency::Signet => InvoiceNetwork::Signet,
+            Currency::Regtest | Currency::Simnet => InvoiceNetwork::Regtest,
+        }
+    }
+}
+
+/// A BOLT11 invoice decoded into the fields callers actually need, instead of
+/// the raw `lightning_invoice` type
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DecodedInvoice {
+    pub payment_hash: String,
+    /// `None` for an amountless invoice; the caller must prompt the user for one
+    pub amount_msat: Option<u64>,
+    pub description: Option<String>,
+    pub description_hash: Option<String>,
+    pub payee_pubkey: String,
+    pub timestamp: u64,
+    pub expiry_seconds: u64,
+    pub min_final_cltv_expiry: u64,
+    pub network: InvoiceNetwork,
+    pub is_expired: bool,
+}
+
+/// Decodes a BOLT11 invoice into a `DecodedInvoice`, rejecting it if it was
+/// issued for a network other than `expected_network`
+pub fn decode_invoice_detailed(
+    payment_request: &str,
+    expected_network: InvoiceNetwork,
+) -> Result<DecodedInvoice> {
+    let invoice = decode_invoice(payment_request)?;
+
+    let network = InvoiceNetwork::from(invoice.currency());
+    if network != expected_network {
+        bail!("invoice is for {network:?}, expected {expected_network:?}");
+    }
+
+    let (description, description_hash) = match invoice.description() {
+        InvoiceDescription::Direct(description) => (Some(description.to_string()), None),
+        InvoiceDescription::Hash(hash) => (None, Some(hash.0.to_string())),
+    };
+
+    let timestamp = invoice
+        .timestamp()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let expiry_seconds = invoice.expiry_time().as_secs();
+    let is_expired = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs() > timestamp + expiry_seconds)
+        .unwrap_or(false);
+
+    Ok(DecodedInvoice {
+        payment_hash: invoice.payment_hash().to_string(),
+        amount_msat: invoice.amount_milli_satoshis(),
+        description,
+        description_hash,
+        payee_pubkey: invoice.recover_payee_pub_key().to_string(),
+        timestamp,
+        expiry_seconds,
+        min_final_cltv_expiry: invoice.min_final_cltv_expiry_delta(),
+        network,
+        is_expired,
+    })
+}
+
 /// Get user lightning balance
-pub async fn get_balance(token: &str) -> Result<Vec<Account>> {
+pub async fn get_balance(token: &str, refresh: &str) -> Result<Vec<Account>> {
     let endpoint = LNDHUB_ENDPOINT.to_string();
-    let url = format!("{endpoint}/balance");
-    let response = get(&url, Some(token)).await?;
-    let balance: BalancesResponse = serde_json::from_str(&response)?;
-    let mut accounts = Vec::new();
-    for (_, value) in balance.accounts {
-        accounts.push(value);
-    }
+    with_refresh(token, refresh, move |token| {
+        let url = format!("{endpoint}/balance");
+        async move {
+            let response = get(&url, Some(&token)).await?;
+            let balance: BalancesResponse = parse_lndhub_response(&response)?;
+            let mut accounts = Vec::new();
+            for (_, value) in balance.accounts {
+                accounts.push(value);
+            }
 
-    Ok(accounts)
+            Ok(accounts)
+        }
+    })
+    .await
+}
+
+/// Get user lightning balance converted to `currency` at the current exchange rate.
+/// Only BTC-denominated accounts are converted using the fetched rate; accounts
+/// already denominated in `currency` are added as-is. Accounts denominated in some
+/// other fiat currency aren't included, since the price subsystem only prices
+/// BTC against `currency`, not fiat against fiat.
+pub async fn get_balance_fiat(token: &str, refresh: &str, currency: &str) -> Result<f64> {
+    let accounts = get_balance(token, refresh).await?;
+    let currency = currency.to_uppercase();
+    let rate = fetch_rate(&currency, None).await?;
+
+    let total = accounts
+        .iter()
+        .map(|account| {
+            let balance: f64 = account.balance.parse().unwrap_or(0.0);
+            match account.currency.to_uppercase() {
+                ref c if c == "BTC" => balance * rate,
+                ref c if *c == currency => balance,
+                _ => 0.0,
+            }
+        })
+        .sum();
+
+    Ok(total)
 }
 
 /// Pay a lightning invoice
-pub async fn pay_invoice(payment_request: &str, token: &str) -> Result<PayInvoiceResponse> {
+pub async fn pay_invoice(
+    payment_request: &str,
+    token: &str,
+    refresh: &str,
+) -> Result<PayInvoiceResponse> {
     let endpoint = LNDHUB_ENDPOINT.to_string();
-    let url = format!("{endpoint}/payinvoice");
-    let req = PayInvoiceRequest {
-        payment_request: payment_request.to_string(),
-    };
-    let response = post_json_auth(&url, &Some(req), Some(token)).await?;
-    let response: PayInvoiceResponse = serde_json::from_str(&response)?;
+    let payment_request = payment_request.to_string();
+    with_refresh(token, refresh, move |token| {
+        let url = format!("{endpoint}/payinvoice");
+        let req = PayInvoiceRequest {
+            payment_request: payment_request.clone(),
+        };
+        async move {
+            let response = post_json_auth(&url, &Some(req), Some(&token)).await?;
+            let response: PayInvoiceResponse = parse_lndhub_response(&response)?;
+
+            Ok(response)
+        }
+    })
+    .await
+}
+
+/// Lifecycle of a payment after `pay_invoice` has accepted it, since routing
+/// can take time or fail after the initial request was acknowledged
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PaymentStatus {
+    Pending,
+    Complete,
+    Failed,
+}
+
+/// Status of a previously-sent payment, looked up by payment hash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaymentStatusResponse {
+    pub payment_hash: String,
+    pub status: PaymentStatus,
+    pub payment_preimage: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Looks up the current status of a payment by its payment hash
+pub async fn get_payment_status(
+    payment_hash: &str,
+    token: &str,
+    refresh: &str,
+) -> Result<PaymentStatusResponse> {
+    let endpoint = LNDHUB_ENDPOINT.to_string();
+    let payment_hash = payment_hash.to_string();
+    with_refresh(token, refresh, move |token| {
+        let url = format!("{endpoint}/paymentstatus/{payment_hash}");
+        async move {
+            let response = get(&url, Some(&token)).await?;
+            let status: PaymentStatusResponse = parse_lndhub_response(&response)?;
+
+            Ok(status)
+        }
+    })
+    .await
+}
+
+/// Pays `payment_request` and polls `get_payment_status` until it resolves to
+/// `Complete` or `Failed`, or `timeout` elapses. Returns the payment preimage
+/// on success so callers don't have to treat an in-flight payment as a failure.
+pub async fn pay_invoice_and_track(
+    payment_request: &str,
+    token: &str,
+    refresh: &str,
+    timeout: std::time::Duration,
+) -> Result<String> {
+    let response = pay_invoice(payment_request, token, refresh).await?;
+    if !response.success {
+        bail!(
+            "payment failed: {}",
+            response.error.unwrap_or_else(|| "unknown error".to_string())
+        );
+    }
+    if let Some(preimage) = response.payment_preimage {
+        return Ok(preimage);
+    }
+
+    let deadline = std::time::Instant::now() + timeout;
+    let poll_interval = std::time::Duration::from_secs(1);
+    loop {
+        let status = get_payment_status(&response.payment_hash, token, refresh).await?;
+        match status.status {
+            PaymentStatus::Complete => {
+                return status
+                    .payment_preimage
+                    .ok_or_else(|| anyhow::anyhow!("payment completed without a preimage"));
+            }
+            PaymentStatus::Failed => {
+                bail!(
+                    "payment failed: {}",
+                    status.error.unwrap_or_else(|| "unknown error".to_string())
+                );
+            }
+            PaymentStatus::Pending => {}
+        }
 
-    Ok(response)
+        if std::time::Instant::now() >= deadline {
+            bail!("timed out waiting for payment {} to resolve", response.payment_hash);
+        }
+        tokio::time::sleep(poll_interval).await;
+    }
 }
 
 /// Get successful lightning transactions user made. Order newest to oldest.
-pub async fn get_txs(token: &str) -> Result<Vec<Transaction>> {
+pub async fn get_txs(token: &str, refresh: &str) -> Result<Vec<Transaction>> {
     let endpoint = LNDHUB_ENDPOINT.to_string();
-    let url = format!("{endpoint}/gettxs");
-    let response = get(&url, Some(token)).await?;
-    let txs = serde_json::from_str(&response)?;
+    with_refresh(token, refresh, move |token| {
+        let url = format!("{endpoint}/gettxs");
+        async move {
+            let response = get(&url, Some(&token)).await?;
+            let txs = parse_lndhub_response(&response)?;
+
+            Ok(txs)
+        }
+    })
+    .await
+}
+
+/// A lightning transaction alongside its `inbound_amount` converted to `currency`
+/// at the exchange rate applicable at `created_at`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PricedTransaction {
+    #[serde(flatten)]
+    pub tx: Transaction,
+    pub fiat_amount: f64,
+    pub fiat_currency: String,
+}
+
+/// Get successful lightning transactions priced in `currency`, reusing a single
+/// rate per timestamp across the whole batch instead of refetching it per transaction
+pub async fn get_txs_fiat(
+    token: &str,
+    refresh: &str,
+    currency: &str,
+) -> Result<Vec<PricedTransaction>> {
+    let txs = get_txs(token, refresh).await?;
+    let mut rates = RateCache::new();
+    let mut priced = Vec::with_capacity(txs.len());
+    for tx in txs {
+        let rate = rates.get_rate(currency, Some(tx.created_at)).await?;
+        let btc_amount: f64 = tx.inbound_amount.parse().unwrap_or(0.0);
+        priced.push(PricedTransaction {
+            fiat_amount: btc_amount * rate,
+            fiat_currency: currency.to_uppercase(),
+            tx,
+        });
+    }
 
-    Ok(txs)
+    Ok(priced)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// BOLT11 spec example: an amountless mainnet invoice ("Please consider
+    /// supporting this project"), issued in 2017 so it's long since expired
+    const TEST_INVOICE: &str = "lnbc1pvjluezpp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdq5xysxxatsyp3k7enxv4jsxqzpuaztrnwngzn3kdzw5hydlzf03qdgm2hdq27cqv3agm2awhz5se903vruatfhq77w3ls4evs3ch9zw97j25emudupq63nyw24cg27h2rspfj9srp";
+
+    #[test]
+    fn decodes_an_amountless_mainnet_invoice() {
+        let decoded = decode_invoice_detailed(TEST_INVOICE, InvoiceNetwork::Bitcoin).unwrap();
+
+        assert_eq!(decoded.network, InvoiceNetwork::Bitcoin);
+        assert_eq!(decoded.amount_msat, None);
+        assert!(!decoded.payee_pubkey.is_empty());
+    }
+
+    #[test]
+    fn flags_a_long_expired_invoice_as_expired() {
+        let decoded = decode_invoice_detailed(TEST_INVOICE, InvoiceNetwork::Bitcoin).unwrap();
+
+        assert!(decoded.is_expired);
+    }
+
+    #[test]
+    fn rejects_an_invoice_on_the_wrong_network() {
+        let err = decode_invoice_detailed(TEST_INVOICE, InvoiceNetwork::Testnet).unwrap_err();
+
+        assert!(err.to_string().contains("Testnet"));
+    }
 }
@@ -0,0 +1,141 @@
+use crate::{
+    operations::lightning::{decode_invoice, pay_invoice, PayInvoiceResponse},
+    util::get,
+};
+use anyhow::{anyhow, bail, Result};
+use bech32::FromBase32;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+
+/// LNURL-pay parameters returned by the initial callback
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LnurlPayParams {
+    pub tag: String,
+    pub callback: String,
+    #[serde(rename = "minSendable")]
+    pub min_sendable: u64,
+    #[serde(rename = "maxSendable")]
+    pub max_sendable: u64,
+    pub metadata: String,
+    #[serde(rename = "commentAllowed")]
+    pub comment_allowed: Option<u32>,
+}
+
+/// Invoice returned by the LNURL-pay callback
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LnurlPayCallbackResponse {
+    pub pr: String,
+    pub routes: Option<Vec<serde_json::Value>>,
+}
+
+/// Resolves a Lightning Address (`user@domain`) or a bech32 `lnurl1...` string
+/// into the `https://` URL it points to
+fn resolve_lnurl(address_or_lnurl: &str) -> Result<String> {
+    if let Some((user, domain)) = address_or_lnurl.split_once('@') {
+        let user = utf8_percent_encode(user, NON_ALPHANUMERIC);
+        return Ok(format!("https://{domain}/.well-known/lnurlp/{user}"));
+    }
+
+    let (hrp, data, _variant) = bech32::decode(address_or_lnurl)
+        .map_err(|e| anyhow!("invalid lnurl: {e}"))?;
+    if hrp != "lnurl" {
+        bail!("unexpected lnurl hrp: {hrp}");
+    }
+    let bytes = Vec::<u8>::from_base32(&data)?;
+    let url = String::from_utf8(bytes)?;
+
+    Ok(url)
+}
+
+/// Fetches the LNURL-pay parameters for a Lightning Address or bech32 lnurl string
+pub async fn get_lnurl_pay_params(address_or_lnurl: &str) -> Result<LnurlPayParams> {
+    let url = resolve_lnurl(address_or_lnurl)?;
+    let response = get(&url, None).await?;
+    let params: LnurlPayParams = serde_json::from_str(&response)?;
+
+    if params.tag != "payRequest" {
+        bail!("lnurl endpoint is not a payRequest: {}", params.tag);
+    }
+
+    Ok(params)
+}
+
+/// Pays a Lightning Address or LNURL-pay endpoint for `amount_msat` millisatoshis,
+/// optionally attaching a comment, and routes the resulting invoice through
+/// the existing custodial `pay_invoice` flow
+pub async fn pay_lnurl(
+    address_or_lnurl: &str,
+    amount_msat: u64,
+    comment: Option<&str>,
+    token: &str,
+    refresh: &str,
+) -> Result<PayInvoiceResponse> {
+    let params = get_lnurl_pay_params(address_or_lnurl).await?;
+
+    if amount_msat < params.min_sendable || amount_msat > params.max_sendable {
+        bail!(
+            "amount {amount_msat} msat outside of allowed range [{}, {}]",
+            params.min_sendable,
+            params.max_sendable
+        );
+    }
+
+    let separator = if params.callback.contains('?') { '&' } else { '?' };
+    let mut callback_url = format!("{}{separator}amount={amount_msat}", params.callback);
+    if let Some(comment) = comment {
+        let comment = utf8_percent_encode(comment, NON_ALPHANUMERIC);
+        callback_url.push_str(&format!("&comment={comment}"));
+    }
+
+    let response = get(&callback_url, None).await?;
+    let callback: LnurlPayCallbackResponse = serde_json::from_str(&response)?;
+
+    let invoice = decode_invoice(&callback.pr)?;
+    let invoice_msat = invoice
+        .amount_milli_satoshis()
+        .ok_or_else(|| anyhow!("lnurl callback returned an amountless invoice"))?;
+    if invoice_msat != amount_msat {
+        bail!(
+            "invoice amount {invoice_msat} msat does not match requested {amount_msat} msat"
+        );
+    }
+
+    pay_invoice(&callback.pr, token, refresh).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bech32::ToBase32;
+
+    #[test]
+    fn resolves_lightning_address() {
+        let url = resolve_lnurl("alice@example.com").unwrap();
+        assert_eq!(url, "https://example.com/.well-known/lnurlp/alice");
+    }
+
+    #[test]
+    fn percent_encodes_the_lightning_address_user_part() {
+        let url = resolve_lnurl("a&b=c@example.com").unwrap();
+        assert_eq!(url, "https://example.com/.well-known/lnurlp/a%26b%3Dc");
+    }
+
+    #[test]
+    fn resolves_bech32_lnurl() {
+        let original = "https://example.com/lnurlp/alice";
+        let encoded =
+            bech32::encode("lnurl", original.as_bytes().to_base32(), bech32::Variant::Bech32)
+                .unwrap();
+
+        let resolved = resolve_lnurl(&encoded).unwrap();
+        assert_eq!(resolved, original);
+    }
+
+    #[test]
+    fn rejects_wrong_hrp() {
+        let encoded =
+            bech32::encode("notlnurl", b"hi".to_base32(), bech32::Variant::Bech32).unwrap();
+
+        assert!(resolve_lnurl(&encoded).is_err());
+    }
+}
@@ -0,0 +1,4 @@
+pub mod lightning;
+pub mod lnurl;
+pub mod price;
+pub mod swap;
@@ -0,0 +1,125 @@
+use crate::util::get;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+
+const PRICE_ENDPOINT: &str = "https://mempool.space/api/v1/historical-price";
+
+/// A single price point as returned by the historical price endpoint, keyed
+/// by the fiat currencies it carries (e.g. `"USD"`, `"EUR"`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PricePoint {
+    pub time: u64,
+    #[serde(flatten)]
+    pub rates: HashMap<String, f64>,
+}
+
+/// Historical/live price response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PriceResponse {
+    prices: Vec<PricePoint>,
+}
+
+/// Fetches the BTC/`currency` exchange rate, either the live rate (`at: None`)
+/// or the rate at a given unix timestamp
+pub async fn fetch_rate(currency: &str, at: Option<u64>) -> Result<f64> {
+    let currency = currency.to_uppercase();
+    let url = match at {
+        Some(timestamp) => format!("{PRICE_ENDPOINT}?currency={currency}&timestamp={timestamp}"),
+        None => format!("{PRICE_ENDPOINT}?currency={currency}"),
+    };
+    let response = get(&url, None).await?;
+    let parsed: PriceResponse = serde_json::from_str(&response)?;
+    let point = parsed
+        .prices
+        .first()
+        .ok_or_else(|| anyhow!("no price data returned for {currency}"))?;
+    let rate = point
+        .rates
+        .get(&currency)
+        .ok_or_else(|| anyhow!("no {currency} rate in price response"))?;
+
+    Ok(*rate)
+}
+
+/// Caches BTC/fiat rates keyed by `(currency, timestamp)` so pricing a batch
+/// of transactions doesn't refetch the same rate over and over. `timestamp`
+/// of `0` is used as the sentinel key for the live rate.
+#[derive(Debug, Default)]
+pub struct RateCache {
+    rates: HashMap<(String, u64), f64>,
+}
+
+impl RateCache {
+    pub fn new() -> Self {
+        Self {
+            rates: HashMap::new(),
+        }
+    }
+
+    /// Returns the cached rate for `(currency, at)`, fetching and caching it
+    /// on first use. `at: None` is treated as the live rate.
+    pub async fn get_rate(&mut self, currency: &str, at: Option<u64>) -> Result<f64> {
+        self.get_rate_with(currency, at, fetch_rate).await
+    }
+
+    /// Same as `get_rate`, but with the network fetch passed in, so the
+    /// dedup-by-`(currency, timestamp)` logic can be tested without a network call
+    async fn get_rate_with<F, Fut>(&mut self, currency: &str, at: Option<u64>, fetch: F) -> Result<f64>
+    where
+        F: FnOnce(&str, Option<u64>) -> Fut,
+        Fut: Future<Output = Result<f64>>,
+    {
+        let key = (currency.to_uppercase(), at.unwrap_or(0));
+        if let Some(rate) = self.rates.get(&key) {
+            return Ok(*rate);
+        }
+
+        let rate = fetch(currency, at).await?;
+        self.rates.insert(key, rate);
+
+        Ok(rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[tokio::test]
+    async fn fetches_a_rate_only_once_per_currency_and_timestamp() {
+        let calls = RefCell::new(Vec::new());
+        let mut cache = RateCache::new();
+
+        let fetch = |currency: &str, at: Option<u64>| {
+            calls.borrow_mut().push((currency.to_string(), at));
+            async move { Ok(42.0) }
+        };
+
+        cache.get_rate_with("usd", Some(100), fetch).await.unwrap();
+        cache.get_rate_with("usd", Some(100), fetch).await.unwrap();
+        cache.get_rate_with("usd", Some(200), fetch).await.unwrap();
+        cache.get_rate_with("eur", Some(100), fetch).await.unwrap();
+
+        assert_eq!(calls.borrow().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn shares_the_live_rate_entry_with_timestamp_zero() {
+        let calls = RefCell::new(0u32);
+        let mut cache = RateCache::new();
+
+        let fetch = |_: &str, _: Option<u64>| {
+            *calls.borrow_mut() += 1;
+            async move { Ok(1.0) }
+        };
+
+        cache.get_rate_with("usd", None, fetch).await.unwrap();
+        cache.get_rate_with("usd", None, fetch).await.unwrap();
+        cache.get_rate_with("usd", Some(0), fetch).await.unwrap();
+
+        assert_eq!(*calls.borrow(), 1);
+    }
+}
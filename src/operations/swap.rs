@@ -0,0 +1,139 @@
+use crate::{
+    data::constants::LNDHUB_ENDPOINT,
+    operations::lightning::{get_txs, parse_lndhub_response, with_refresh, Transaction},
+    util::{get, post_json_auth},
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Lifecycle of a submarine swap-in
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SwapStatus {
+    /// Swap created, waiting for an on-chain deposit
+    Created,
+    /// A deposit transaction was seen in the mempool
+    Detected,
+    /// The deposit transaction reached the required confirmations
+    Confirmed,
+    /// The deposit was credited to the user's Lightning balance
+    Credited,
+    Failed,
+}
+
+/// An on-chain deposit address and swap terms for topping up a custodial
+/// Lightning balance with on-chain BTC
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwapInfo {
+    pub swap_id: String,
+    pub bitcoin_address: String,
+    pub lockup_script: String,
+    pub refund_address: Option<String>,
+    pub lockup_txid: Option<String>,
+    pub status: SwapStatus,
+    pub min_amount: u64,
+    pub max_amount: u64,
+}
+
+/// Swap-in creation request
+#[derive(Debug, Serialize, Deserialize)]
+struct CreateSwapInRequest {
+    amount: u64,
+}
+
+/// Opens a submarine swap-in: deposit `amount_sat` on-chain to the returned
+/// address and it is credited to the custodial Lightning balance once confirmed
+pub async fn create_swap_in(amount_sat: u64, token: &str, refresh: &str) -> Result<SwapInfo> {
+    let endpoint = LNDHUB_ENDPOINT.to_string();
+    with_refresh(token, refresh, move |token| {
+        let url = format!("{endpoint}/swapin");
+        let req = CreateSwapInRequest { amount: amount_sat };
+        async move {
+            let response = post_json_auth(&url, &Some(req), Some(&token)).await?;
+            let swap: SwapInfo = parse_lndhub_response(&response)?;
+
+            Ok(swap)
+        }
+    })
+    .await
+}
+
+/// Fetches the current status of a swap-in, including whether the deposit
+/// has been detected, confirmed, and credited
+pub async fn get_swap_status(swap_id: &str, token: &str, refresh: &str) -> Result<SwapInfo> {
+    let endpoint = LNDHUB_ENDPOINT.to_string();
+    let swap_id = swap_id.to_string();
+    with_refresh(token, refresh, move |token| {
+        let url = format!("{endpoint}/swapin/{swap_id}");
+        async move {
+            let response = get(&url, Some(&token)).await?;
+            let swap: SwapInfo = parse_lndhub_response(&response)?;
+
+            Ok(swap)
+        }
+    })
+    .await
+}
+
+/// Finds the `get_txs` entry that credited a given swap-in, if the on-chain
+/// deposit has been credited to the Lightning balance yet
+pub async fn find_swap_credit(
+    swap_id: &str,
+    token: &str,
+    refresh: &str,
+) -> Result<Option<Transaction>> {
+    let txs = get_txs(token, refresh).await?;
+
+    Ok(find_swap_credit_in(txs, swap_id))
+}
+
+/// Correlates `swap_id` against a list of transactions, in isolation from the
+/// network call so the matching logic can be unit tested
+fn find_swap_credit_in(txs: Vec<Transaction>, swap_id: &str) -> Option<Transaction> {
+    txs.into_iter()
+        .find(|tx| tx.swap_id.as_deref() == Some(swap_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(swap_id: Option<&str>) -> Transaction {
+        Transaction {
+            txid: "txid".to_string(),
+            fee_txid: None,
+            outbound_txid: None,
+            inbound_txid: None,
+            created_at: 0,
+            outbound_amount: "0".to_string(),
+            inbound_amount: "0".to_string(),
+            outbound_account_id: "out".to_string(),
+            inbound_account_id: "in".to_string(),
+            outbound_uid: 0,
+            inbound_uid: 0,
+            outbound_currency: "BTC".to_string(),
+            inbound_currency: "BTC".to_string(),
+            exchange_rate: "1".to_string(),
+            tx_type: "swap".to_string(),
+            fees: "0".to_string(),
+            reference: None,
+            swap_id: swap_id.map(|s| s.to_string()),
+        }
+    }
+
+    #[test]
+    fn finds_the_transaction_matching_the_swap_id() {
+        let txs = vec![tx(None), tx(Some("other-swap")), tx(Some("the-swap"))];
+
+        let found = find_swap_credit_in(txs, "the-swap").unwrap();
+
+        assert_eq!(found.swap_id.as_deref(), Some("the-swap"));
+    }
+
+    #[test]
+    fn returns_none_when_no_transaction_credited_the_swap_yet() {
+        let txs = vec![tx(None), tx(Some("other-swap"))];
+
+        assert!(find_swap_credit_in(txs, "the-swap").is_none());
+    }
+}